@@ -0,0 +1,99 @@
+//! Physical-device selection.
+//!
+//! Enumerates every Vulkan device, keeps the ones that support a compute
+//! queue family able to present to our surface and the extensions we
+//! actually need, then scores the rest the way typical suitability checks
+//! do -- discrete GPUs first, then integrated, then anything else -- and
+//! falls back to the highest-scoring one. An explicit override by
+//! enumeration index or device-name substring takes precedence.
+
+use std::sync::Arc;
+
+use vulkano::device::DeviceExtensions;
+use vulkano::instance::{Instance, PhysicalDevice, PhysicalDeviceType};
+use vulkano::swapchain::Surface;
+use winit::window::Window;
+
+/// Narrows device selection to a specific device instead of scoring.
+pub enum DeviceOverride {
+    /// The device's 0-based position in enumeration order.
+    Index(usize),
+    /// The first device whose name contains this substring
+    /// (case-insensitive).
+    NameSubstring(String),
+}
+
+/// Picks the physical device to render with.
+///
+/// Only devices with a queue family that supports both compute and
+/// presenting to `surface`, and that expose `required_extensions`, are
+/// considered. Panics if none qualify, or if `override_` doesn't match any
+/// qualifying device.
+pub fn select<'a>(
+    instance: &'a Arc<Instance>,
+    surface: &Surface<Window>,
+    required_extensions: &DeviceExtensions,
+    override_: Option<&DeviceOverride>,
+) -> PhysicalDevice<'a> {
+    let candidates: Vec<PhysicalDevice<'a>> = PhysicalDevice::enumerate(instance)
+        .filter(|dev| is_suitable(dev, surface, required_extensions))
+        .collect();
+
+    if candidates.is_empty() {
+        panic!("no device supports a compute queue family, presentation, and the required extensions");
+    }
+
+    if let Some(override_) = override_ {
+        return match override_ {
+            DeviceOverride::Index(index) => candidates
+                .into_iter()
+                .find(|dev| dev.index() == *index)
+                .unwrap_or_else(|| panic!("no suitable device at index {}", index)),
+            DeviceOverride::NameSubstring(substring) => {
+                let needle = substring.to_ascii_lowercase();
+                candidates
+                    .into_iter()
+                    .find(|dev| device_name(dev).to_ascii_lowercase().contains(&needle))
+                    .unwrap_or_else(|| panic!("no suitable device matching {:?}", substring))
+            }
+        };
+    }
+
+    candidates
+        .into_iter()
+        .max_by_key(|dev| score(dev))
+        .unwrap()
+}
+
+fn is_suitable(
+    dev: &PhysicalDevice,
+    surface: &Surface<Window>,
+    required_extensions: &DeviceExtensions,
+) -> bool {
+    let has_compute_present_queue = dev
+        .queue_families()
+        .any(|q| q.supports_compute() && surface.is_supported(q).unwrap_or(false));
+
+    let supported = DeviceExtensions::supported_by_device(*dev);
+    let has_extensions = supported.intersection(required_extensions) == *required_extensions;
+
+    has_compute_present_queue && has_extensions
+}
+
+/// Higher is more suitable: prefer discrete GPUs, then integrated, then
+/// whatever else is left (e.g. CPU or virtual devices).
+fn score(dev: &PhysicalDevice) -> u32 {
+    match dev.properties().device_type {
+        Some(PhysicalDeviceType::DiscreteGpu) => 2,
+        Some(PhysicalDeviceType::IntegratedGpu) => 1,
+        _ => 0,
+    }
+}
+
+fn device_name(dev: &PhysicalDevice) -> &str {
+    dev.properties()
+        .device_name
+        .as_ref()
+        .expect("encountered unnamed device")
+        .as_str()
+}
@@ -4,80 +4,215 @@ use vulkano::{
     device::{Device, DeviceExtensions, Features},
     buffer::{BufferUsage, CpuAccessibleBuffer},
     command_buffer::{
-        AutoCommandBufferBuilder, CommandBufferUsage, PrimaryCommandBuffer
+        AutoCommandBufferBuilder, CommandBufferUsage
     },
-    sync::GpuFuture,
-    format::{Format},
-    image::{
-        ImageDimensions, StorageImage,
-        view::ImageView,
+    swapchain::{
+        self, AcquireError, Swapchain, SwapchainCreationError,
+        FullscreenExclusive, SurfaceTransform, PresentMode,
     },
-    pipeline::{ComputePipeline, ComputePipelineAbstract},
+    sync::{self, FlushError, GpuFuture},
+    image::{ImageUsage, SwapchainImage, view::ImageView},
+    format::Format,
+    pipeline::ComputePipelineAbstract,
     descriptor::descriptor_set::PersistentDescriptorSet,
 };
+use vulkano_win::VkSurfaceBuild;
+use winit::{
+    event::{Event, WindowEvent, MouseButton, ElementState, MouseScrollDelta},
+    event_loop::{ControlFlow, EventLoop},
+    window::WindowBuilder,
+    dpi::PhysicalPosition,
+};
+use clap::Parser;
 use std::sync::Arc;
-use image::{ImageBuffer, Rgba};
 
-mod cs {
-    vulkano_shaders::shader!{
-        ty: "compute",
-        src: "
-#version 450
+mod device;
+mod perturbation;
+mod shader;
+mod tiling;
+
+/// Command-line options controlling the initial viewport, render quality,
+/// and the escape-time formula itself.
+#[derive(Parser)]
+#[clap(name = "vulkan-mandelbrot")]
+struct Args {
+    /// Real part of the initial viewport center.
+    #[clap(long, default_value_t = -0.5)]
+    center_re: f32,
+    /// Imaginary part of the initial viewport center.
+    #[clap(long, default_value_t = 0.0)]
+    center_im: f32,
+    /// Half-width of the initial viewport, in the complex plane.
+    #[clap(long, default_value_t = 1.0)]
+    scale: f64,
+    /// Number of iterations before a point is considered non-escaping.
+    #[clap(long, default_value_t = 200)]
+    max_iterations: u32,
+    /// `|z|` threshold past which a point is considered to have escaped.
+    #[clap(long, default_value_t = 4.0)]
+    escape_radius: f32,
+    /// GLSL expression for one iteration step, in terms of `z` and `c`.
+    /// Defaults to the classic quadratic map; pass e.g.
+    /// `"cmul(cmul(z, z), z) + c"` for the cubic `z^3 + c` family.
+    #[clap(long, default_value = "cmul(z, z) + c")]
+    fractal_fn: String,
+    /// Render a Julia set with this fixed constant (`re,im`) instead of the
+    /// Mandelbrot set.
+    #[clap(long, value_parser = parse_julia_constant)]
+    julia: Option<[f32; 2]>,
+    /// Render with perturbation-theory deep zoom instead of direct float
+    /// iteration, for magnifications beyond ~1e-14 where `center`/`scale`
+    /// collapse to the same float. Ignores `--fractal-fn` and `--julia`.
+    #[clap(long)]
+    deep_zoom: bool,
+    /// Arbitrary-precision real part of the deep-zoom reference orbit's
+    /// center. Only used with `--deep-zoom`.
+    #[clap(long, default_value = "-0.5")]
+    deep_zoom_center_re: String,
+    /// Arbitrary-precision imaginary part of the deep-zoom reference
+    /// orbit's center. Only used with `--deep-zoom`.
+    #[clap(long, default_value = "0.0")]
+    deep_zoom_center_im: String,
+    /// Force a specific device by its index in the "Devices found" list,
+    /// instead of picking the highest-scoring one automatically.
+    #[clap(long, conflicts_with = "gpu_name")]
+    gpu_index: Option<usize>,
+    /// Force the first device whose name contains this substring
+    /// (case-insensitive), instead of picking the highest-scoring one
+    /// automatically.
+    #[clap(long, conflicts_with = "gpu_index")]
+    gpu_name: Option<String>,
+    /// Render a single image to this PNG path and exit, instead of opening
+    /// the interactive viewer. Rendered in tiles so `--width`/`--height`
+    /// can exceed the device's maximum image dimensions.
+    #[clap(long)]
+    output: Option<std::path::PathBuf>,
+    /// Output width in pixels, for `--output`.
+    #[clap(long, default_value_t = 1024)]
+    width: u32,
+    /// Output height in pixels, for `--output`.
+    #[clap(long, default_value_t = 1024)]
+    height: u32,
+    /// Maximum tile side length in pixels, for `--output`.
+    #[clap(long, default_value_t = 1024)]
+    tile_size: u32,
+}
 
-layout(local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
-layout(set = 0, binding = 0, rgba8) uniform writeonly image2D img;
+fn parse_julia_constant(s: &str) -> Result<[f32; 2], String> {
+    let (re, im) = s.split_once(',').ok_or("expected `re,im`")?;
+    let re: f32 = re.trim().parse().map_err(|_| "invalid real part")?;
+    let im: f32 = im.trim().parse().map_err(|_| "invalid imaginary part")?;
+    Ok([re, im])
+}
 
-// https://web.archive.org/web/20210803061024/https://en.wikipedia.org/wiki/HSL_and_HSV#HSV_to_RGB_alternative
-const float nR = 5.0;
-const float nG = 3.0;
-const float nB = 1.0;
+impl Args {
+    fn fractal_mode(&self) -> shader::Mode {
+        match self.julia {
+            Some(c) => shader::Mode::Julia { c },
+            None => shader::Mode::Mandelbrot,
+        }
+    }
 
-float get_f(float n, float i) {
-    float k = mod(n + 6.0*i, 6);
-    return (1.0 - i) * (1.0 - max(0.0, min(k, min(4.0 - k, 1.0))));
+    fn device_override(&self) -> Option<device::DeviceOverride> {
+        if let Some(index) = self.gpu_index {
+            Some(device::DeviceOverride::Index(index))
+        } else {
+            self.gpu_name.clone().map(device::DeviceOverride::NameSubstring)
+        }
+    }
 }
 
-void main() {
-    vec2 norm_coordinates = (gl_GlobalInvocationID.xy + vec2(0.5)) /
-                            vec2(imageSize(img));
-    
-    vec2 c = (norm_coordinates - vec2(0.5)) * 2.0 - vec2(1.0, 0.0);
-
-    vec2 z = vec2(0.0, 0.0);
-    float i;
-    for (i = 0.0; i < 1.0; i += 0.005) {
-        z = vec2(
-            z.x * z.x - z.y * z.y + c.x,
-            z.y * z.x + z.x * z.y + c.y
-        );
+/// Pan/zoom state for the interactive viewer, expressed in the same
+/// normalized complex-plane coordinates the shader maps screen pixels into.
+/// Kept in `f64` so dragging/scrolling don't themselves cap how deep
+/// `--deep-zoom` can go -- the classic shader only needs `f32` and is
+/// downcast to it in [`View::push_constants`].
+///
+/// In `--deep-zoom` mode, `center` isn't an absolute coordinate (the
+/// arbitrary-precision deep-zoom center lives only in the reference orbit
+/// already baked into the GPU buffer) -- it's the accumulated drag offset
+/// *from* that center, which is why it starts at the origin there instead
+/// of at `args.center_re`/`args.center_im`.
+struct View {
+    center: [f64; 2],
+    scale: f64,
+}
 
-        if (length(z) > 4.0) {
-            break;
+impl View {
+    fn from_args(args: &Args) -> Self {
+        let center = if args.deep_zoom {
+            [0.0, 0.0]
+        } else {
+            [args.center_re as f64, args.center_im as f64]
+        };
+        View { center, scale: args.scale }
+    }
+
+    fn push_constants(&self, args: &Args, output_size: [u32; 2]) -> shader::PushConstants {
+        shader::PushConstants {
+            center: [self.center[0] as f32, self.center[1] as f32],
+            scale: self.scale as f32,
+            max_iterations: args.max_iterations,
+            escape_radius: args.escape_radius,
+            _pad: 0,
+            output_size,
+            tile_offset: [0, 0],
         }
     }
 
-    // R
-    float R = get_f(nR, i);
-    // G
-    float G = get_f(nG, i);
-    // B
-    float B = get_f(nB, i);
+    fn perturbation_push_constants(
+        &self,
+        args: &Args,
+        orbit_len: u32,
+    ) -> shader::PerturbationPushConstants {
+        shader::PerturbationPushConstants {
+            scale: self.scale,
+            max_iterations: args.max_iterations,
+            escape_radius: args.escape_radius,
+            orbit_len,
+            _pad: [0; 3],
+            pan: self.center,
+        }
+    }
+}
 
-    vec4 to_write = vec4(vec3(R, G, B), 1.0);
-    imageStore(img, ivec2(gl_GlobalInvocationID.xy), to_write);
+/// Which compute pipeline is driving the render: the classic direct-float
+/// escape-time shader, or the perturbation-theory deep-zoom shader with its
+/// reference-orbit buffer.
+enum Renderer {
+    Classic {
+        pipeline: Arc<dyn ComputePipelineAbstract + Send + Sync>,
+    },
+    DeepZoom {
+        pipeline: Arc<dyn ComputePipelineAbstract + Send + Sync>,
+        orbit_buffer: Arc<CpuAccessibleBuffer<[perturbation::OrbitPoint]>>,
+        orbit_len: u32,
+    },
 }
-"
+
+impl Renderer {
+    fn pipeline(&self) -> &Arc<dyn ComputePipelineAbstract + Send + Sync> {
+        match self {
+            Renderer::Classic { pipeline } => pipeline,
+            Renderer::DeepZoom { pipeline, .. } => pipeline,
+        }
     }
 }
 
 fn main() {
+    let args = Args::parse();
+
+    let required_extensions = vulkano_win::required_extensions();
     let instance =
-        Instance::new(
-            None, Version::V1_2, &InstanceExtensions::none(), None
-        )
+        Instance::new(None, Version::V1_2, &required_extensions, None)
         .expect("failed to create instance");
-    
-    // get physical device
+
+    let event_loop = EventLoop::new();
+    let surface = WindowBuilder::new()
+        .with_title("vulkan-mandelbrot")
+        .build_vk_surface(&event_loop, instance.clone())
+        .expect("failed to create window surface");
+
     println!("Devices found:");
     for dev in PhysicalDevice::enumerate(&instance) {
         println!("{}",
@@ -85,100 +220,272 @@ fn main() {
                 .expect("encountered unnamed device")
         );
     };
-    let physical_device =
-        PhysicalDevice::enumerate(&instance)
-        .find(|dev|
-            dev.properties().device_name.as_ref()
-            .expect("encountered unnamed device")
-            // for some reason compute shaders are jank on the Quadro
-            .to_ascii_lowercase().contains("quadro")
-        )
-        .expect("failed to find specified device");
 
-    // get a queue family that supports what we need (graphics/compute)
+    let device_extensions = DeviceExtensions {
+        khr_swapchain: true,
+        ..DeviceExtensions::none()
+    };
+    let physical_device = device::select(
+        &instance, &surface, &device_extensions, args.device_override().as_ref()
+    );
+
+    // get a queue family that supports compute and presenting to our surface
     let queue_family =
         physical_device.queue_families()
-        .find(|&q| q.supports_compute())
-        .expect("failed to find a compute queue family");
-    
-    // get a device and queue for the above queue family
+        .find(|&q| q.supports_compute() && surface.is_supported(q).unwrap_or(false))
+        .expect("failed to find a compute queue family that can present");
+
+    // get a device and queue for the above queue family. `--deep-zoom`'s
+    // perturbation shader reads the reference orbit in double precision, so
+    // it needs shaderFloat64 -- only request it when actually rendering in
+    // that mode.
+    let features = Features {
+        shader_f3264: args.deep_zoom,
+        ..Features::none()
+    };
     let (device, mut queues) =
         Device::new(
             physical_device,
-            &Features::none(),
-            &DeviceExtensions::none(),
+            &features,
+            &device_extensions,
             [(queue_family, 0.5)].iter().cloned()
         )
         .expect("failed to create device");
     let queue = queues.next().unwrap();
 
-    // create image
-    let image = StorageImage::new(
-        device.clone(),
-        ImageDimensions::Dim2d { width: 1024, height: 1024, array_layers: 1 },
-        Format::R8G8B8A8Unorm, Some(queue.family())
-    ).unwrap();
-    
-    // load shader and create compute pipeline
-    let shader = cs::Shader::load(device.clone())
-        .expect("failed to create shader module");
-    let compute_pipeline = Arc::new(
-        ComputePipeline::new(
-            device.clone(), &shader.main_entry_point(), &(), None
-        )
-        .expect("failed to create compute pipeline")
-    );
+    if let Some(output) = &args.output {
+        if args.deep_zoom {
+            panic!("--output tiled export does not yet support --deep-zoom");
+        }
+        let pipeline =
+            shader::build_pipeline(device.clone(), &args.fractal_fn, &args.fractal_mode());
+        let output_size = [args.width, args.height];
+        let image = tiling::render(
+            device.clone(), queue.clone(), pipeline,
+            args.width, args.height, args.tile_size,
+            |tile| shader::PushConstants {
+                center: [args.center_re, args.center_im],
+                scale: args.scale as f32,
+                max_iterations: args.max_iterations,
+                escape_radius: args.escape_radius,
+                _pad: 0,
+                output_size,
+                tile_offset: tile.offset,
+            },
+        );
+        image.save(output).expect("failed to save output PNG");
+        return;
+    }
 
-    // create descriptor set
-    let layout = compute_pipeline.layout().descriptor_set_layout(0).unwrap();
-    let set = Arc::new(PersistentDescriptorSet::start(layout.clone())
-        .add_image(
-            ImageView::new(image.clone()).unwrap()
-        ).unwrap()
-        .build().unwrap()
-    );
+    // create the swapchain, with STORAGE usage so the compute shader can
+    // dispatch directly into the acquired images
+    let (mut swapchain, mut images) = {
+        let caps = surface.capabilities(physical_device)
+            .expect("failed to query surface capabilities");
+        // `supported_formats[0]` is often a BGRA/sRGB swapchain format that
+        // either rejects STORAGE_IMAGE usage outright or, since the shader
+        // always writes `rgba8`, would swap the red and blue channels. Pick
+        // the first R8G8B8A8Unorm format the surface reports instead of
+        // assuming the default; STORAGE usage support is not checked here.
+        let (format, color_space) = caps
+            .supported_formats
+            .iter()
+            .copied()
+            .find(|&(format, _)| format == Format::R8G8B8A8Unorm)
+            .expect("device does not support an R8G8B8A8Unorm swapchain format");
+        let dimensions: [u32; 2] = surface.window().inner_size().into();
 
-    // create buffer accessible by cpu (image buffers normally are not)
-    let buf = CpuAccessibleBuffer::from_iter(
-        device.clone(), BufferUsage::all(), false,
-        (0 .. 1024*1024*4).map(|_| 0u8)
-    ).expect("failed to create CpuAccessibleBuffer");
-    
-    // create command buffer
-    let mut builder = AutoCommandBufferBuilder::primary(
-        device.clone(), queue.family(), CommandBufferUsage::OneTimeSubmit
-    ).unwrap();
-    builder
-        .dispatch(
-            [1024 / 8, 1024 / 8, 1], compute_pipeline.clone(), set.clone(), (),
-            None
-        ).unwrap()
-        .copy_image_to_buffer(image.clone(), buf.clone()).unwrap();
-    let command_buffer =
-        builder.build().expect("failed to build command buffer");
-    
-    // submit command buffer and wait for execution to finish
-    let finished =
-        command_buffer.execute(queue.clone())
-        .expect("failed to execute command buffer");
-    finished.then_signal_fence_and_flush().unwrap().wait(None).unwrap();
-
-    // read and save resulting image
-    let buffer_content = buf.read().unwrap();
-    let image = ImageBuffer::<Rgba<u8>, _>::from_raw(
-        1024, 1024, &buffer_content[..]
-    ).unwrap();
-    image.save("image.png").unwrap();
-
-    // read and print buffer
-    print!("data:");
-    let mut i: u32 = 0;
-    for x in buffer_content.iter() {
-        if i == 0 {
-            print!(" {}", x);
-            i = 1023;
-        };
-        i -= 1;
+        Swapchain::start(device.clone(), surface.clone())
+            .num_images(caps.min_image_count)
+            .format(format)
+            .dimensions(dimensions)
+            .usage(ImageUsage { storage: true, ..ImageUsage::color_attachment() })
+            .sharing_mode(&queue)
+            .composite_alpha(caps.supported_composite_alpha.iter().next().unwrap())
+            .transform(SurfaceTransform::Identity)
+            .present_mode(PresentMode::Fifo)
+            .fullscreen_exclusive(FullscreenExclusive::Default)
+            .clipped(true)
+            .color_space(color_space)
+            .build()
+            .expect("failed to create swapchain")
+    };
+
+    // compile and load the compute pipeline: either the direct-float
+    // escape-time shader, or the perturbation-theory deep-zoom shader
+    let renderer = if args.deep_zoom {
+        let orbit = perturbation::compute_reference_orbit(
+            &args.deep_zoom_center_re,
+            &args.deep_zoom_center_im,
+            args.max_iterations,
+            args.escape_radius,
+        );
+        let orbit_len = orbit.len() as u32;
+        let orbit_buffer = CpuAccessibleBuffer::from_iter(
+            device.clone(), BufferUsage::all(), false, orbit.into_iter()
+        ).expect("failed to upload reference orbit");
+        Renderer::DeepZoom {
+            pipeline: shader::build_perturbation_pipeline(device.clone()),
+            orbit_buffer,
+            orbit_len,
+        }
+    } else {
+        Renderer::Classic {
+            pipeline: shader::build_pipeline(device.clone(), &args.fractal_fn, &args.fractal_mode()),
+        }
     };
-    println!();
-}
\ No newline at end of file
+
+    let mut descriptor_sets = make_descriptor_sets(&renderer, &images);
+
+    let mut view = View::from_args(&args);
+    let mut recreate_swapchain = false;
+    let mut dragging = false;
+    let mut last_cursor_pos = PhysicalPosition::new(0.0, 0.0);
+    let mut previous_frame_end =
+        Some(sync::now(device.clone()).boxed());
+
+    event_loop.run(move |event, _, control_flow| {
+        match event {
+            Event::WindowEvent { event: WindowEvent::CloseRequested, .. } => {
+                *control_flow = ControlFlow::Exit;
+            }
+            Event::WindowEvent { event: WindowEvent::Resized(_), .. } => {
+                recreate_swapchain = true;
+            }
+            Event::WindowEvent {
+                event: WindowEvent::MouseInput { state, button: MouseButton::Left, .. },
+                ..
+            } => {
+                dragging = state == ElementState::Pressed;
+            }
+            Event::WindowEvent { event: WindowEvent::CursorMoved { position, .. }, .. } => {
+                if dragging {
+                    let dims: [u32; 2] = surface.window().inner_size().into();
+                    let dx = (position.x - last_cursor_pos.x) / dims[0] as f64;
+                    let dy = (position.y - last_cursor_pos.y) / dims[1] as f64;
+                    // pixels dragged right/down should move the view the same
+                    // direction the content appears to move under the cursor
+                    view.center[0] -= dx * 2.0 * view.scale;
+                    view.center[1] -= dy * 2.0 * view.scale;
+                }
+                last_cursor_pos = position;
+            }
+            Event::WindowEvent { event: WindowEvent::MouseWheel { delta, .. }, .. } => {
+                let scroll = match delta {
+                    MouseScrollDelta::LineDelta(_, y) => y as f64,
+                    MouseScrollDelta::PixelDelta(p) => p.y / 100.0,
+                };
+                view.scale *= (1.0 - scroll * 0.1).max(0.01);
+            }
+            Event::RedrawEventsCleared => {
+                previous_frame_end.as_mut().unwrap().cleanup_finished();
+
+                if recreate_swapchain {
+                    let dimensions: [u32; 2] = surface.window().inner_size().into();
+                    let (new_swapchain, new_images) =
+                        match swapchain.recreate().dimensions(dimensions).build() {
+                            Ok(r) => r,
+                            Err(SwapchainCreationError::UnsupportedDimensions) => return,
+                            Err(e) => panic!("failed to recreate swapchain: {:?}", e),
+                        };
+                    swapchain = new_swapchain;
+                    images = new_images;
+                    descriptor_sets = make_descriptor_sets(&renderer, &images);
+                    recreate_swapchain = false;
+                }
+
+                let (image_index, suboptimal, acquire_future) =
+                    match swapchain::acquire_next_image(swapchain.clone(), None) {
+                        Ok(r) => r,
+                        Err(AcquireError::OutOfDate) => {
+                            recreate_swapchain = true;
+                            return;
+                        }
+                        Err(e) => panic!("failed to acquire next image: {:?}", e),
+                    };
+                if suboptimal {
+                    recreate_swapchain = true;
+                }
+
+                let dims = images[image_index].dimensions().width_height();
+
+                // the acquired image starts in `PresentSrc` layout (or
+                // undefined, the first time); transition it to `General` so
+                // the compute shader can write it, then back to `PresentSrc`
+                // before presenting. vulkano's automatic barrier insertion
+                // performs these transitions based on the usages recorded
+                // below (storage-image write, then present).
+                let mut builder = AutoCommandBufferBuilder::primary(
+                    device.clone(), queue.family(), CommandBufferUsage::OneTimeSubmit
+                ).unwrap();
+                let dispatch_size = [(dims[0] + 7) / 8, (dims[1] + 7) / 8, 1];
+                match &renderer {
+                    Renderer::Classic { pipeline } => {
+                        builder.dispatch(
+                            dispatch_size,
+                            pipeline.clone(),
+                            descriptor_sets[image_index].clone(),
+                            view.push_constants(&args, dims),
+                            None,
+                        ).unwrap();
+                    }
+                    Renderer::DeepZoom { pipeline, orbit_len, .. } => {
+                        builder.dispatch(
+                            dispatch_size,
+                            pipeline.clone(),
+                            descriptor_sets[image_index].clone(),
+                            view.perturbation_push_constants(&args, *orbit_len),
+                            None,
+                        ).unwrap();
+                    }
+                };
+                let command_buffer = builder.build().unwrap();
+
+                let future = previous_frame_end.take().unwrap()
+                    .join(acquire_future)
+                    .then_execute(queue.clone(), command_buffer)
+                    .unwrap()
+                    .then_swapchain_present(queue.clone(), swapchain.clone(), image_index)
+                    .then_signal_fence_and_flush();
+
+                previous_frame_end = match future {
+                    Ok(future) => Some(future.boxed()),
+                    Err(FlushError::OutOfDate) => {
+                        recreate_swapchain = true;
+                        Some(sync::now(device.clone()).boxed())
+                    }
+                    Err(e) => {
+                        println!("failed to flush future: {:?}", e);
+                        Some(sync::now(device.clone()).boxed())
+                    }
+                };
+            }
+            _ => {}
+        }
+    });
+}
+
+/// Builds one descriptor set per swapchain image, since the compute shader
+/// targets a different `image2D` every frame. The deep-zoom renderer's
+/// descriptor set also binds its reference-orbit buffer.
+fn make_descriptor_sets(
+    renderer: &Renderer,
+    images: &[Arc<SwapchainImage<winit::window::Window>>],
+) -> Vec<Arc<PersistentDescriptorSet>> {
+    let layout = renderer.pipeline().layout().descriptor_set_layout(0).unwrap();
+    match renderer {
+        Renderer::Classic { .. } => images.iter().map(|image| {
+            Arc::new(PersistentDescriptorSet::start(layout.clone())
+                .add_image(ImageView::new(image.clone()).unwrap()).unwrap()
+                .build().unwrap()
+            ) as Arc<PersistentDescriptorSet>
+        }).collect(),
+        Renderer::DeepZoom { orbit_buffer, .. } => images.iter().map(|image| {
+            Arc::new(PersistentDescriptorSet::start(layout.clone())
+                .add_image(ImageView::new(image.clone()).unwrap()).unwrap()
+                .add_buffer(orbit_buffer.clone()).unwrap()
+                .build().unwrap()
+            ) as Arc<PersistentDescriptorSet>
+        }).collect(),
+    }
+}
@@ -0,0 +1,72 @@
+//! High-precision reference-orbit computation for perturbation-theory deep
+//! zoom.
+//!
+//! Single (and even double) precision floats lose all detail past roughly
+//! `1e-14` zoom, because every pixel's `c` coordinate rounds to the same
+//! value. Perturbation theory sidesteps this: a single reference orbit
+//! `Z_0, Z_1, ...` is computed once at a chosen center `C` using arbitrary
+//! precision, then every pixel iterates only its small *delta* from that
+//! orbit in ordinary float. See [`crate::shader::build_perturbation_pipeline`]
+//! for the per-pixel recurrence.
+
+use rug::{Complex, Float};
+
+/// Working precision (bits) of the reference-orbit arithmetic, high enough
+/// to resolve magnifications around `1e-300`.
+const PRECISION_BITS: u32 = 1024;
+
+/// One point `Z_n` of the reference orbit, downcast from [`PRECISION_BITS`]
+/// to `f64` for upload to the GPU. Even though `|Z_n|` stays bounded by the
+/// escape radius, deep orbits routinely pass near-periodic points where
+/// `Z_n` itself needs far more than `f32`'s ~7 significant digits to avoid
+/// injecting its own perturbation glitches -- `f64` is the standard
+/// compromise between that and what the GPU can consume directly.
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct OrbitPoint {
+    pub re: f64,
+    pub im: f64,
+}
+
+/// Computes `Z_0 = 0, Z_{n+1} = Z_n^2 + C` at [`PRECISION_BITS`] precision,
+/// stopping at `max_iterations` or once `|Z_n|` exceeds `escape_radius`.
+///
+/// `center_re`/`center_im` are parsed as arbitrary-precision decimal
+/// strings, since by the time a zoom is deep enough to need this module a
+/// plain `f32`/`f64` center has already collapsed to the nearest
+/// representable value.
+pub fn compute_reference_orbit(
+    center_re: &str,
+    center_im: &str,
+    max_iterations: u32,
+    escape_radius: f32,
+) -> Vec<OrbitPoint> {
+    let parse = |s: &str| {
+        Float::parse(s)
+            .unwrap_or_else(|e| panic!("invalid deep-zoom center coordinate {:?}: {}", s, e))
+    };
+    let c = Complex::with_val(
+        PRECISION_BITS,
+        (
+            Float::with_val(PRECISION_BITS, parse(center_re)),
+            Float::with_val(PRECISION_BITS, parse(center_im)),
+        ),
+    );
+
+    let mut z = Complex::with_val(PRECISION_BITS, (0, 0));
+    let mut orbit = Vec::with_capacity(max_iterations as usize);
+    let escape_radius = escape_radius as f64;
+
+    for _ in 0..max_iterations {
+        let (re, im) = (z.real().to_f64(), z.imag().to_f64());
+        orbit.push(OrbitPoint { re, im });
+
+        if !re.is_finite() || !im.is_finite() || (re * re + im * im).sqrt() > escape_radius {
+            break;
+        }
+
+        z = z.square() + &c;
+    }
+
+    orbit
+}
@@ -0,0 +1,449 @@
+//! Runtime compilation of the escape-time compute shader.
+//!
+//! Instead of baking one fixed iteration formula into the binary via
+//! `vulkano_shaders::shader!`, the shader source is assembled from
+//! [`TEMPLATE`] by splicing in the user's iteration expression and fractal
+//! mode, compiled to SPIR-V with `shaderc`, and loaded into a
+//! [`ShaderModule`] at runtime. This lets users explore arbitrary `z^n + c`
+//! families and Julia constants without rebuilding the crate.
+
+use std::ffi::CStr;
+use std::sync::Arc;
+
+use vulkano::descriptor::descriptor::{
+    DescriptorBufferDesc, DescriptorDesc, DescriptorDescTy, DescriptorImageDesc,
+    DescriptorImageDescArray, DescriptorImageDescDimensions, ShaderStages,
+};
+use vulkano::descriptor::pipeline_layout::{PipelineLayoutDesc, PipelineLayoutDescPcRange};
+use vulkano::device::Device;
+use vulkano::pipeline::shader::{ShaderInterfaceDef, ShaderInterfaceDefEntry, ShaderModule};
+use vulkano::pipeline::{ComputePipeline, ComputePipelineAbstract};
+
+/// Which family of escape-time fractal the compiled shader renders.
+pub enum Mode {
+    /// `z` starts at the origin and `c` is the pixel's position.
+    Mandelbrot,
+    /// `z` starts at the pixel's position and `c` is a fixed constant.
+    Julia { c: [f32; 2] },
+}
+
+const TEMPLATE: &str = "
+#version 450
+
+layout(local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+layout(set = 0, binding = 0, rgba8) uniform writeonly image2D img;
+
+layout(push_constant) uniform PushConstants {
+    vec2 center;
+    float scale;
+    uint max_iterations;
+    float escape_radius;
+    uvec2 output_size;
+    uvec2 tile_offset;
+} pc;
+
+vec2 cmul(vec2 a, vec2 b) {
+    return vec2(a.x * b.x - a.y * b.y, a.x * b.y + a.y * b.x);
+}
+
+// https://web.archive.org/web/20210803061024/https://en.wikipedia.org/wiki/HSL_and_HSV#HSV_to_RGB_alternative
+const float nR = 5.0;
+const float nG = 3.0;
+const float nB = 1.0;
+
+float get_f(float n, float i) {
+    float k = mod(n + 6.0*i, 6);
+    return (1.0 - i) * (1.0 - max(0.0, min(k, min(4.0 - k, 1.0))));
+}
+
+void main() {
+    // `output_size`/`tile_offset` let this dispatch be one tile of a
+    // larger logical image: the pixel's position in the complex plane
+    // depends on the full output size, not just this tile's own image.
+    vec2 pixel = vec2(gl_GlobalInvocationID.xy + pc.tile_offset) + vec2(0.5);
+    vec2 norm_coordinates = pixel / vec2(pc.output_size);
+    vec2 coord = pc.center + (norm_coordinates - vec2(0.5)) * 2.0 * pc.scale;
+
+    vec2 z = __Z0__;
+    vec2 c = __C__;
+
+    uint n;
+    float z_len = length(z);
+    for (n = 0; n < pc.max_iterations; n++) {
+        z = __ITERATION__;
+
+        z_len = length(z);
+        if (z_len > pc.escape_radius) {
+            break;
+        }
+    }
+
+    // Normalized iteration count: smooths the discrete per-iteration bands
+    // into a continuous gradient by using how far past the escape radius
+    // `z` overshot, rather than just the integer step it escaped on.
+    float nu = float(n);
+    if (n < pc.max_iterations) {
+        nu = float(n) + 1.0 - log2(log2(z_len));
+    }
+    float i = clamp(nu / float(pc.max_iterations), 0.0, 1.0);
+
+    float R = get_f(nR, i);
+    float G = get_f(nG, i);
+    float B = get_f(nB, i);
+
+    vec4 to_write = vec4(vec3(R, G, B), 1.0);
+    imageStore(img, ivec2(gl_GlobalInvocationID.xy), to_write);
+}
+";
+
+/// Splices `iteration_expr` (a GLSL expression in `z` and `c`, e.g.
+/// `"cmul(z, z) + c"` for the classic Mandelbrot/Julia map, or
+/// `"cmul(cmul(z, z), z) + c"` for `z^3 + c`) and `mode` into [`TEMPLATE`].
+pub fn build_source(iteration_expr: &str, mode: &Mode) -> String {
+    let (z0, c) = match mode {
+        Mode::Mandelbrot => ("vec2(0.0, 0.0)".to_string(), "coord".to_string()),
+        Mode::Julia { c } => ("coord".to_string(), format!("vec2({}, {})", c[0], c[1])),
+    };
+    TEMPLATE
+        .replace("__Z0__", &z0)
+        .replace("__C__", &c)
+        .replace("__ITERATION__", iteration_expr)
+}
+
+/// Compiles GLSL compute shader source to SPIR-V words with `shaderc`.
+pub fn compile_to_spirv(source: &str) -> Vec<u32> {
+    let compiler = shaderc::Compiler::new().expect("failed to create shaderc compiler");
+    let artifact = compiler
+        .compile_into_spirv(
+            source,
+            shaderc::ShaderKind::Compute,
+            "mandelbrot.comp",
+            "main",
+            None,
+        )
+        .expect("failed to compile runtime GLSL to SPIR-V");
+    artifact.as_binary().to_vec()
+}
+
+/// Descriptor-set and push-constant layout matching [`TEMPLATE`]: one
+/// storage-image binding at set 0 binding 0, and one push-constant range
+/// covering the `PushConstants` block, std430-sized via
+/// `size_of::<PushConstants>()`.
+#[derive(Debug, Copy, Clone)]
+struct Layout(ShaderStages);
+
+unsafe impl PipelineLayoutDesc for Layout {
+    fn num_sets(&self) -> usize {
+        1
+    }
+
+    fn num_bindings_in_set(&self, set: usize) -> Option<usize> {
+        match set {
+            0 => Some(1),
+            _ => None,
+        }
+    }
+
+    fn descriptor(&self, set: usize, binding: usize) -> Option<DescriptorDesc> {
+        match (set, binding) {
+            (0, 0) => Some(DescriptorDesc {
+                ty: DescriptorDescTy::Image(DescriptorImageDesc {
+                    sampled: false,
+                    dimensions: DescriptorImageDescDimensions::TwoDimensional,
+                    format: None,
+                    multisampled: false,
+                    array_layers: DescriptorImageDescArray::NonArrayed,
+                }),
+                array_count: 1,
+                stages: self.0,
+                readonly: false,
+            }),
+            _ => None,
+        }
+    }
+
+    fn num_push_constants_ranges(&self) -> usize {
+        1
+    }
+
+    fn push_constants_range(&self, num: usize) -> Option<PipelineLayoutDescPcRange> {
+        match num {
+            0 => Some(PipelineLayoutDescPcRange {
+                offset: 0,
+                size: std::mem::size_of::<PushConstants>(),
+                stages: self.0,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Layout-compatible mirror of the `PushConstants` block in [`TEMPLATE`].
+///
+/// std430 gives `uvec2` an 8-byte alignment, so `output_size` must start at
+/// byte offset 24, not 20 -- `_pad` reserves the 4 bytes the GLSL compiler
+/// inserts between `escape_radius` and `output_size`. Without it, this
+/// struct packs to 36 bytes instead of the block's actual 40, and every
+/// field from `output_size` on reads one `u32` short of where the shader
+/// expects it.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct PushConstants {
+    pub center: [f32; 2],
+    pub scale: f32,
+    pub max_iterations: u32,
+    pub escape_radius: f32,
+    pub _pad: u32,
+    pub output_size: [u32; 2],
+    pub tile_offset: [u32; 2],
+}
+
+struct NoInterface;
+
+unsafe impl ShaderInterfaceDef for NoInterface {
+    type Iter = std::vec::IntoIter<ShaderInterfaceDefEntry>;
+
+    fn elements(&self) -> Self::Iter {
+        Vec::new().into_iter()
+    }
+}
+
+/// Compiles `iteration_expr`/`mode` into SPIR-V and builds a compute
+/// pipeline from it, bypassing `vulkano_shaders`' compile-time reflection
+/// with the manual [`Layout`] above.
+pub fn build_pipeline(
+    device: Arc<Device>,
+    iteration_expr: &str,
+    mode: &Mode,
+) -> Arc<dyn ComputePipelineAbstract + Send + Sync> {
+    let source = build_source(iteration_expr, mode);
+    let spirv = compile_to_spirv(&source);
+
+    unsafe {
+        let module = ShaderModule::from_words(device.clone(), &spirv)
+            .expect("failed to load runtime-compiled shader module");
+
+        let stages = ShaderStages { compute: true, ..ShaderStages::none() };
+        let entry_point = module.compute_entry_point(
+            CStr::from_bytes_with_nul(b"main\0").unwrap(),
+            NoInterface,
+            NoInterface,
+            Layout(stages),
+        );
+
+        Arc::new(
+            ComputePipeline::new(device, &entry_point, &(), None)
+                .expect("failed to create compute pipeline from runtime-compiled shader"),
+        ) as Arc<dyn ComputePipelineAbstract + Send + Sync>
+    }
+}
+
+/// Deep-zoom compute shader driven by perturbation theory (see
+/// [`crate::perturbation`]): each pixel iterates its small `delta` from a
+/// precomputed reference orbit `Z_n` instead of iterating `c` directly, so
+/// the loop stays accurate at magnifications where `c` itself would have
+/// collapsed to a single representable float.
+const PERTURBATION_SOURCE: &str = "
+#version 450
+#extension GL_ARB_gpu_shader_fp64 : require
+
+layout(local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+layout(set = 0, binding = 0, rgba8) uniform writeonly image2D img;
+// the reference orbit in double precision: Z_n stays bounded by the escape
+// radius, but near mini-brots it needs far more than float's ~7 significant
+// digits, which is exactly the precision this whole renderer exists to
+// recover
+layout(set = 0, binding = 1) readonly buffer ReferenceOrbit {
+    dvec2 z[];
+} reference_orbit;
+
+layout(push_constant) uniform PushConstants {
+    // `scale`/`pan` are the whole reason this renderer exists: at the deep
+    // zooms this mode targets (down to ~1e-300) a `float` has already
+    // underflowed to zero, so both stay double all the way from the CLI
+    // down to `delta_c` below.
+    double scale;
+    uint max_iterations;
+    float escape_radius;
+    uint orbit_len;
+    // accumulated mouse-drag offset from the reference orbit's center, in
+    // the same complex-plane units as `scale`
+    dvec2 pan;
+} pc;
+
+dvec2 cmul(dvec2 a, dvec2 b) {
+    return dvec2(a.x * b.x - a.y * b.y, a.x * b.y + a.y * b.x);
+}
+
+// https://web.archive.org/web/20210803061024/https://en.wikipedia.org/wiki/HSL_and_HSV#HSV_to_RGB_alternative
+const float nR = 5.0;
+const float nG = 3.0;
+const float nB = 1.0;
+
+float get_f(float n, float i) {
+    float k = mod(n + 6.0*i, 6);
+    return (1.0 - i) * (1.0 - max(0.0, min(k, min(4.0 - k, 1.0))));
+}
+
+void main() {
+    vec2 norm_coordinates = (gl_GlobalInvocationID.xy + vec2(0.5)) /
+                            vec2(imageSize(img));
+    // this pixel's offset from the reference orbit's center C, computed
+    // entirely in double (`scale`/`pan` are already double push constants)
+    // so the recurrence below never rounds the zoom level down to float
+    // before it gets a chance to matter
+    dvec2 delta_c = dvec2(norm_coordinates - vec2(0.5)) * 2.0 * pc.scale + pc.pan;
+    double escape_radius = double(pc.escape_radius);
+
+    dvec2 delta = dvec2(0.0);
+    uint ref_index = 0;
+    uint n;
+    float escaped_len = 0.0;
+    bool escaped = false;
+
+    for (n = 0; n < pc.max_iterations; n++) {
+        // delta_{n+1} from Z_n and delta_n
+        dvec2 Z_n = reference_orbit.z[ref_index];
+        delta = cmul(2.0 * Z_n, delta) + cmul(delta, delta) + delta_c;
+        ref_index++;
+
+        // Z_{n+1} + delta_{n+1}, the pixel's true (un-perturbed) position
+        dvec2 full = reference_orbit.z[ref_index < pc.orbit_len ? ref_index : 0] + delta;
+        double full_len = length(full);
+
+        if (full_len > escape_radius) {
+            escaped = true;
+            escaped_len = float(full_len);
+            break;
+        }
+
+        // Rebasing: once the true value is small again but the delta has
+        // grown large relative to the reference orbit, or once the
+        // reference orbit has no point after this one (it escaped or was
+        // truncated), restart from Z_0 with `delta` holding the pixel's
+        // true position -- otherwise the orbit and the delta drift apart
+        // and the image glitches.
+        if (full_len < length(delta) || ref_index + 1 >= pc.orbit_len) {
+            delta = full;
+            ref_index = 0;
+        }
+    }
+
+    float nu = float(n);
+    if (escaped) {
+        nu = float(n) + 1.0 - log2(log2(escaped_len));
+    }
+    float i = clamp(nu / float(pc.max_iterations), 0.0, 1.0);
+
+    float R = get_f(nR, i);
+    float G = get_f(nG, i);
+    float B = get_f(nB, i);
+
+    vec4 to_write = vec4(vec3(R, G, B), 1.0);
+    imageStore(img, ivec2(gl_GlobalInvocationID.xy), to_write);
+}
+";
+
+/// Descriptor-set and push-constant layout matching
+/// [`PERTURBATION_SOURCE`]: the same storage-image binding as [`Layout`]
+/// plus a read-only storage buffer at set 0 binding 1 for the reference
+/// orbit, and a push-constant range covering [`PerturbationPushConstants`].
+#[derive(Debug, Copy, Clone)]
+struct PerturbationLayout(ShaderStages);
+
+unsafe impl PipelineLayoutDesc for PerturbationLayout {
+    fn num_sets(&self) -> usize {
+        1
+    }
+
+    fn num_bindings_in_set(&self, set: usize) -> Option<usize> {
+        match set {
+            0 => Some(2),
+            _ => None,
+        }
+    }
+
+    fn descriptor(&self, set: usize, binding: usize) -> Option<DescriptorDesc> {
+        match (set, binding) {
+            (0, 0) => Some(DescriptorDesc {
+                ty: DescriptorDescTy::Image(DescriptorImageDesc {
+                    sampled: false,
+                    dimensions: DescriptorImageDescDimensions::TwoDimensional,
+                    format: None,
+                    multisampled: false,
+                    array_layers: DescriptorImageDescArray::NonArrayed,
+                }),
+                array_count: 1,
+                stages: self.0,
+                readonly: false,
+            }),
+            (0, 1) => Some(DescriptorDesc {
+                ty: DescriptorDescTy::Buffer(DescriptorBufferDesc {
+                    dynamic: Some(false),
+                    storage: true,
+                }),
+                array_count: 1,
+                stages: self.0,
+                readonly: true,
+            }),
+            _ => None,
+        }
+    }
+
+    fn num_push_constants_ranges(&self) -> usize {
+        1
+    }
+
+    fn push_constants_range(&self, num: usize) -> Option<PipelineLayoutDescPcRange> {
+        match num {
+            0 => Some(PipelineLayoutDescPcRange {
+                offset: 0,
+                size: std::mem::size_of::<PerturbationPushConstants>(),
+                stages: self.0,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Layout-compatible mirror of the `PushConstants` block in
+/// [`PERTURBATION_SOURCE`].
+///
+/// std430 gives `dvec2` a 16-byte alignment, so `pan` must start at byte
+/// offset 32, not 20 -- `_pad` reserves the 12 bytes the GLSL compiler
+/// inserts between `orbit_len` and `pan`.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct PerturbationPushConstants {
+    pub scale: f64,
+    pub max_iterations: u32,
+    pub escape_radius: f32,
+    pub orbit_len: u32,
+    pub _pad: [u32; 3],
+    pub pan: [f64; 2],
+}
+
+/// Compiles [`PERTURBATION_SOURCE`] and builds a compute pipeline from it.
+pub fn build_perturbation_pipeline(
+    device: Arc<Device>,
+) -> Arc<dyn ComputePipelineAbstract + Send + Sync> {
+    let spirv = compile_to_spirv(PERTURBATION_SOURCE);
+
+    unsafe {
+        let module = ShaderModule::from_words(device.clone(), &spirv)
+            .expect("failed to load perturbation shader module");
+
+        let stages = ShaderStages { compute: true, ..ShaderStages::none() };
+        let entry_point = module.compute_entry_point(
+            CStr::from_bytes_with_nul(b"main\0").unwrap(),
+            NoInterface,
+            NoInterface,
+            PerturbationLayout(stages),
+        );
+
+        Arc::new(
+            ComputePipeline::new(device, &entry_point, &(), None)
+                .expect("failed to create perturbation compute pipeline"),
+        ) as Arc<dyn ComputePipelineAbstract + Send + Sync>
+    }
+}
@@ -0,0 +1,115 @@
+//! Tiled offline rendering, for output resolutions larger than the
+//! device's `maxImageDimension2D` (or than comfortably fits in one
+//! allocation).
+//!
+//! The requested resolution is split into tiles no larger than
+//! `max_tile_dim` on a side. Each tile is rendered into its own small
+//! `StorageImage`, read back, and copied into the correct location of one
+//! combined host-side [`ImageBuffer`], which the caller then saves as a
+//! single PNG.
+
+use std::sync::Arc;
+
+use image::{ImageBuffer, Rgba};
+use vulkano::buffer::{BufferUsage, CpuAccessibleBuffer};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, CommandBufferUsage, PrimaryCommandBuffer};
+use vulkano::device::{Device, Queue};
+use vulkano::descriptor::descriptor_set::PersistentDescriptorSet;
+use vulkano::format::Format;
+use vulkano::image::{view::ImageView, ImageDimensions, StorageImage};
+use vulkano::pipeline::ComputePipelineAbstract;
+use vulkano::sync::GpuFuture;
+
+/// The pixel offset of a tile within the full output image, and that
+/// tile's own size.
+#[derive(Copy, Clone)]
+pub struct Tile {
+    pub offset: [u32; 2],
+    pub size: [u32; 2],
+}
+
+/// Splits a `width`x`height` image into tiles no larger than `max_tile_dim`
+/// on a side, row-major.
+pub fn layout(width: u32, height: u32, max_tile_dim: u32) -> Vec<Tile> {
+    let mut tiles = Vec::new();
+    let mut y = 0;
+    while y < height {
+        let tile_h = max_tile_dim.min(height - y);
+        let mut x = 0;
+        while x < width {
+            let tile_w = max_tile_dim.min(width - x);
+            tiles.push(Tile { offset: [x, y], size: [tile_w, tile_h] });
+            x += tile_w;
+        }
+        y += tile_h;
+    }
+    tiles
+}
+
+/// Renders `width`x`height` by dispatching `pipeline` once per tile (each
+/// tile no larger than `max_tile_dim` on a side) and assembling the results
+/// into one combined image.
+///
+/// `push_constants_for_tile` builds the push constants for a given tile,
+/// typically carrying the tile's offset and the full output size so the
+/// shader can map each pixel to the correct position in the complex plane
+/// regardless of which tile it's in.
+pub fn render<Pc: Copy>(
+    device: Arc<Device>,
+    queue: Arc<Queue>,
+    pipeline: Arc<dyn ComputePipelineAbstract + Send + Sync>,
+    width: u32,
+    height: u32,
+    max_tile_dim: u32,
+    mut push_constants_for_tile: impl FnMut(Tile) -> Pc,
+) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    let mut combined = ImageBuffer::<Rgba<u8>, Vec<u8>>::new(width, height);
+    let layout = pipeline.layout().descriptor_set_layout(0).unwrap();
+
+    for tile in self::layout(width, height, max_tile_dim) {
+        let [tile_w, tile_h] = tile.size;
+
+        let image = StorageImage::new(
+            device.clone(),
+            ImageDimensions::Dim2d { width: tile_w, height: tile_h, array_layers: 1 },
+            Format::R8G8B8A8Unorm, Some(queue.family())
+        ).expect("failed to allocate tile image");
+
+        let set = Arc::new(
+            PersistentDescriptorSet::start(layout.clone())
+                .add_image(ImageView::new(image.clone()).unwrap()).unwrap()
+                .build().unwrap()
+        ) as Arc<PersistentDescriptorSet>;
+
+        let buf = CpuAccessibleBuffer::from_iter(
+            device.clone(), BufferUsage::all(), false,
+            (0 .. tile_w * tile_h * 4).map(|_| 0u8)
+        ).expect("failed to create tile readback buffer");
+
+        let mut builder = AutoCommandBufferBuilder::primary(
+            device.clone(), queue.family(), CommandBufferUsage::OneTimeSubmit
+        ).unwrap();
+        builder
+            .dispatch(
+                [(tile_w + 7) / 8, (tile_h + 7) / 8, 1],
+                pipeline.clone(), set.clone(), push_constants_for_tile(tile), None,
+            ).unwrap()
+            .copy_image_to_buffer(image.clone(), buf.clone()).unwrap();
+        let command_buffer = builder.build().expect("failed to build tile command buffer");
+
+        command_buffer.execute(queue.clone())
+            .expect("failed to execute tile command buffer")
+            .then_signal_fence_and_flush().unwrap()
+            .wait(None).unwrap();
+
+        let tile_pixels = buf.read().unwrap();
+        let tile_image = ImageBuffer::<Rgba<u8>, _>::from_raw(tile_w, tile_h, &tile_pixels[..])
+            .expect("tile readback buffer had the wrong size");
+
+        for (x, y, pixel) in tile_image.enumerate_pixels() {
+            combined.put_pixel(tile.offset[0] + x, tile.offset[1] + y, *pixel);
+        }
+    }
+
+    combined
+}